@@ -0,0 +1,269 @@
+use std::io::{BufRead, BufReader};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// A single image attached to a completion request.
+pub struct ImageInput {
+    /// Base64-encoded image bytes, in their original encoding.
+    pub data: String,
+    /// Mediatype, e.g. `image/png`.
+    pub mime: String,
+}
+
+/// Abstraction over a vision-capable completion backend, so the same
+/// clipboard-to-description workflow can target llama.cpp's native
+/// `/completion` endpoint as well as hosted OpenAI-compatible servers.
+pub trait Backend {
+    fn complete(&self, prompt: &str, images: &[ImageInput]) -> Result<String>;
+
+    /// Like [`Backend::complete`], but calls `on_token` with each
+    /// incremental piece of the response as it arrives. Stops early,
+    /// returning whatever has accumulated so far, once `interrupted`
+    /// returns `true`.
+    fn complete_stream(
+        &self,
+        prompt: &str,
+        images: &[ImageInput],
+        on_token: &mut dyn FnMut(&str),
+        interrupted: &dyn Fn() -> bool,
+    ) -> Result<String>;
+}
+
+/// Talks to a llama.cpp server's `/completion` and `/embedding` endpoints,
+/// using its `image_data`/`[img-N]` multimodal convention.
+pub struct LlamaCpp {
+    pub host: String,
+    pub port: u16,
+    pub temperature: f32,
+    pub n_predict: u32,
+}
+
+impl LlamaCpp {
+    fn completion_endpoint(&self) -> String {
+        format!("http://{}:{}/completion", self.host, self.port)
+    }
+
+    fn embedding_endpoint(&self) -> String {
+        format!("http://{}:{}/embedding", self.host, self.port)
+    }
+
+    fn request(&self, prompt: &str, images: &[ImageInput], stream: bool) -> CompletionRequest {
+        CompletionRequest {
+            prompt: prompt.to_string(),
+            temperature: self.temperature,
+            n_predict: self.n_predict,
+            cache_prompt: true,
+            image_data: to_im_data(images),
+            stop: vec!["USER:".to_string()],
+            stream,
+        }
+    }
+
+    /// Obtains a float embedding vector for `prompt` (optionally alongside
+    /// `images`) from llama.cpp's `/embedding` endpoint.
+    pub fn embed(&self, prompt: &str, images: &[ImageInput]) -> Result<Vec<f32>> {
+        let req = EmbeddingRequest {
+            content: prompt.to_string(),
+            image_data: to_im_data(images),
+        };
+        let resp: EmbeddingResponse = ureq::post(&self.embedding_endpoint())
+            .send_json(&req)?
+            .into_json()?;
+
+        Ok(resp.embedding)
+    }
+}
+
+fn to_im_data(images: &[ImageInput]) -> Vec<ImData> {
+    images.iter()
+        .enumerate()
+        .map(|(i, img)| ImData { data: img.data.clone(), id: i as u32 + 1 })
+        .collect()
+}
+
+impl Backend for LlamaCpp {
+    fn complete(&self, prompt: &str, images: &[ImageInput]) -> Result<String> {
+        let req = self.request(prompt, images, false);
+        let resp: CompletionResponse = ureq::post(&self.completion_endpoint())
+            .send_json(&req)?
+            .into_json()?;
+
+        Ok(resp.content)
+    }
+
+    fn complete_stream(
+        &self,
+        prompt: &str,
+        images: &[ImageInput],
+        on_token: &mut dyn FnMut(&str),
+        interrupted: &dyn Fn() -> bool,
+    ) -> Result<String> {
+        let req = self.request(prompt, images, true);
+        let resp = ureq::post(&self.completion_endpoint()).send_json(&req)?;
+        let reader = BufReader::new(resp.into_reader());
+
+        let mut full = String::new();
+        for line in reader.lines() {
+            if interrupted() {
+                break;
+            }
+            let line = line?;
+            let Some(data) = line.strip_prefix("data: ") else {
+                continue;
+            };
+            let chunk: StreamChunk = serde_json::from_str(data)?;
+            on_token(&chunk.content);
+            full.push_str(&chunk.content);
+            if chunk.stop {
+                break;
+            }
+        }
+
+        Ok(full)
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionRequest {
+    prompt: String,
+    temperature: f32,
+    n_predict: u32,
+    cache_prompt: bool,
+    image_data: Vec<ImData>,
+    stop: Vec<String>,
+    stream: bool,
+}
+
+#[derive(Serialize)]
+struct ImData {
+    data: String,
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    content: String,
+}
+
+/// One `data: {json}` event from llama.cpp's SSE completion stream.
+#[derive(Deserialize)]
+struct StreamChunk {
+    content: String,
+    #[serde(default)]
+    stop: bool,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest {
+    content: String,
+    image_data: Vec<ImData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+/// Talks to an OpenAI-compatible `/chat/completions` endpoint using its
+/// vision message format (`content` as an array of text/image_url parts).
+pub struct OpenAiCompatible {
+    pub endpoint: String,
+    pub model: String,
+    pub n_predict: u32,
+    pub api_key: String,
+}
+
+/// Bounds how long a single `/chat/completions` call is allowed to block,
+/// so a hung hosted backend can't leave Ctrl-C with nothing to interrupt.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+impl OpenAiCompatible {
+    fn request(&self, prompt: &str, images: &[ImageInput]) -> ChatRequest {
+        let mut content = vec![ContentPart::Text { text: prompt.to_string() }];
+        content.extend(images.iter().map(|img| ContentPart::ImageUrl {
+            image_url: ImageUrl { url: format!("data:{};base64,{}", img.mime, img.data) },
+        }));
+
+        ChatRequest {
+            model: self.model.clone(),
+            max_tokens: self.n_predict,
+            messages: vec![ChatMessage { role: "user", content }],
+        }
+    }
+}
+
+impl Backend for OpenAiCompatible {
+    fn complete(&self, prompt: &str, images: &[ImageInput]) -> Result<String> {
+        let req = self.request(prompt, images);
+        let resp: ChatResponse = ureq::post(&self.endpoint)
+            .set("Authorization", &format!("Bearer {}", self.api_key))
+            .timeout(REQUEST_TIMEOUT)
+            .send_json(&req)?
+            .into_json()?;
+
+        Ok(resp.choices.into_iter().next()
+            .map(|c| c.message.content)
+            .unwrap_or_default())
+    }
+
+    fn complete_stream(
+        &self,
+        prompt: &str,
+        images: &[ImageInput],
+        on_token: &mut dyn FnMut(&str),
+        interrupted: &dyn Fn() -> bool,
+    ) -> Result<String> {
+        // The OpenAI chat streaming wire format differs enough (delta chunks,
+        // `[DONE]` sentinel) that it's not worth a separate parser here yet;
+        // fall back to a single non-streamed call and deliver it as one token.
+        // Ctrl-C can't interrupt the call itself once it's in flight, but
+        // REQUEST_TIMEOUT bounds how long it can hang for.
+        if interrupted() {
+            return Ok(String::new());
+        }
+        let content = self.complete(prompt, images)?;
+        on_token(&content);
+        Ok(content)
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    max_tokens: u32,
+    messages: Vec<ChatMessage>,
+}
+
+#[derive(Serialize)]
+struct ChatMessage {
+    role: &'static str,
+    content: Vec<ContentPart>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize)]
+struct ImageUrl {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChatResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatResponseMessage {
+    content: String,
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::Result;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::Deserialize;
+
+use crate::backend::{Backend, ImageInput};
+
+/// Runs cliplm as a long-running Telegram bot: polls `getUpdates`, answers
+/// incoming photos with a description from `backend`, and keeps per-chat
+/// conversation state so follow-up text messages continue the chat using
+/// the same `USER:`/`ASSISTANT:` framing as the CLI mode. A single failed
+/// update (bad response, backend error, ...) is logged and skipped rather
+/// than taking down the whole bot.
+pub fn run(backend: &dyn Backend, token: &str, system_prompt: &str) -> Result<()> {
+    let api = format!("https://api.telegram.org/bot{token}");
+    let mut offset = 0i64;
+    let mut chats: HashMap<i64, String> = HashMap::new();
+
+    loop {
+        let updates = match get_updates(&api, offset) {
+            Ok(updates) => updates,
+            Err(e) => {
+                eprintln!("cliplm: getUpdates failed: {e}");
+                // Back off before retrying so a bad token or an outage
+                // doesn't turn this into a busy-loop hammering Telegram.
+                std::thread::sleep(std::time::Duration::from_secs(2));
+                continue;
+            }
+        };
+
+        for update in updates.result {
+            offset = offset.max(update.update_id + 1);
+
+            if let Err(e) = handle_update(backend, &api, token, system_prompt, &mut chats, update) {
+                eprintln!("cliplm: failed to handle update: {e}");
+            }
+        }
+    }
+}
+
+fn get_updates(api: &str, offset: i64) -> Result<UpdatesResponse> {
+    Ok(ureq::get(&format!("{api}/getUpdates"))
+        .query("timeout", "30")
+        .query("offset", &offset.to_string())
+        .call()?
+        .into_json()?)
+}
+
+fn handle_update(
+    backend: &dyn Backend,
+    api: &str,
+    token: &str,
+    system_prompt: &str,
+    chats: &mut HashMap<i64, String>,
+    update: Update,
+) -> Result<()> {
+    let Some(message) = update.message else { return Ok(()) };
+    let chat_id = message.chat.id;
+    let prompt = chats.entry(chat_id).or_insert_with(|| system_instruction(system_prompt));
+
+    let resp = if let Some(photo) = message.photo.as_deref().and_then(largest_photo) {
+        let data = download_file(api, token, &photo.file_id)?;
+        prompt.push_str("USER: [img-1] Describe the image.\nASSISTANT:");
+        let images = [ImageInput { data, mime: "image/jpeg".to_string() }];
+        backend.complete(prompt, &images)?
+    } else if let Some(text) = message.text {
+        prompt.push_str(&format!("USER: {text}\nASSISTANT:"));
+        backend.complete(prompt, &[])?
+    } else {
+        return Ok(());
+    };
+
+    prompt.push_str(&resp);
+    send_message(api, chat_id, &resp)?;
+
+    Ok(())
+}
+
+/// Strips the CLI default prompt's baked-in `USER: [img-1] Describe the
+/// image.\nASSISTANT:` first turn, keeping just the leading system
+/// instruction so the bot can append its own first turn per chat.
+fn system_instruction(template: &str) -> String {
+    let instruction = template.split("USER:").next().unwrap_or(template).trim_end();
+    format!("{instruction}\n")
+}
+
+fn largest_photo(photos: &[PhotoSize]) -> Option<&PhotoSize> {
+    photos.iter().max_by_key(|p| p.file_size.unwrap_or(0))
+}
+
+fn download_file(api: &str, token: &str, file_id: &str) -> Result<String> {
+    let file: FileResponse = ureq::get(&format!("{api}/getFile"))
+        .query("file_id", file_id)
+        .call()?
+        .into_json()?;
+
+    let url = format!("https://api.telegram.org/file/bot{token}/{}", file.result.file_path);
+    let mut bytes = vec![];
+    ureq::get(&url).call()?.into_reader().read_to_end(&mut bytes)?;
+
+    Ok(BASE64_STANDARD.encode(bytes))
+}
+
+fn send_message(api: &str, chat_id: i64, text: &str) -> Result<()> {
+    ureq::post(&format!("{api}/sendMessage"))
+        .send_json(serde_json::json!({ "chat_id": chat_id, "text": text }))?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct UpdatesResponse {
+    result: Vec<Update>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    photo: Option<Vec<PhotoSize>>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct PhotoSize {
+    file_id: String,
+    file_size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct FileResponse {
+    result: FileResult,
+}
+
+#[derive(Deserialize)]
+struct FileResult {
+    file_path: String,
+}
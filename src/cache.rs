@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::backend::ImageInput;
+
+/// Computes the cache key for a request: a SHA-256 over the raw image
+/// bytes, the prompt text, the sampling parameters that affect the
+/// response, and the backend/model that would answer it, so a cache hit
+/// only happens for byte-identical requests to the same backend.
+pub fn key(images: &[ImageInput], prompt: &str, temperature: f32, n_predict: u32, backend_id: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    for img in images {
+        hasher.update(BASE64_STANDARD.decode(&img.data)?);
+    }
+    hasher.update(prompt.as_bytes());
+    hasher.update(temperature.to_le_bytes());
+    hasher.update(n_predict.to_le_bytes());
+    hasher.update(backend_id.as_bytes());
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Returns the cached response for `key`, if any.
+pub fn get(key: &str) -> Result<Option<String>> {
+    let path = dir()?.join(key);
+    if path.exists() {
+        Ok(Some(std::fs::read_to_string(path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Stores `content` under `key` for future lookups.
+pub fn put(key: &str, content: &str) -> Result<()> {
+    std::fs::write(dir()?.join(key), content)?;
+    Ok(())
+}
+
+fn dir() -> Result<PathBuf> {
+    let dir = dirs::cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("could not determine the user's cache directory"))?
+        .join("cliplm");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
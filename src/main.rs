@@ -1,10 +1,40 @@
-use std::{io::{Cursor, Write}, net::Ipv4Addr, path::PathBuf};
+mod backend;
+mod bot;
+mod cache;
+mod search;
+
+use std::{
+    io::{Cursor, Write},
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Result;
 use base64::{prelude::BASE64_STANDARD, Engine};
 use image::{ImageOutputFormat, ColorType};
-use serde::{Deserialize, Serialize};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use backend::{Backend, ImageInput, LlamaCpp, OpenAiCompatible};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum BackendKind {
+    Llama,
+    Openai,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search the local history of described images by natural-language query.
+    Search {
+        query: String,
+
+        /// Number of results to show.
+        #[clap(short, long, default_value = "5")]
+        top_k: usize,
+    },
+}
 
 #[derive(Parser)]
 struct Args {
@@ -16,14 +46,29 @@ struct Args {
     #[clap(long, default_value = "7001")]
     port: u16,
 
-    /// Initial prompt in format 
+    /// Completion backend to use.
+    #[clap(long, value_enum, default_value = "llama")]
+    backend: BackendKind,
+
+    /// Model name, passed through to `--backend openai` (e.g. `gpt-4-vision-preview`).
+    /// Ignored by the `llama` backend, which just talks to whatever model the
+    /// server already has loaded.
+    #[clap(long)]
+    model: Option<String>,
+
+    /// `/chat/completions` endpoint to use with `--backend openai`. Override
+    /// to target a self-hosted OpenAI-compatible server instead of OpenAI.
+    #[clap(long, env = "CLIPLM_OPENAI_ENDPOINT", default_value = "https://api.openai.com/v1/chat/completions")]
+    openai_endpoint: String,
+
+    /// Initial prompt in format
     /// `<system> USER: <user> ASSISTANT: <empty or handwritten assistant response>`
     #[clap(short, long, default_value = "\
 Assistant is skillful in writing long and detailed description to images.
 USER: [img-1] Describe the image.
 ASSISTANT:"
     )]
-    prompt: String, 
+    prompt: String,
 
     /// Has priority over `--prompt`.
     /// Read initial prompt from file.
@@ -46,53 +91,190 @@ ASSISTANT:"
     #[clap(short, long, default_value = "1024")]
     n_predict: u32,
 
+    /// Image input: a file path or a `data:image/...;base64,...` URL. Repeat
+    /// to reference `[img-1]`, `[img-2]`, etc. in the prompt. If omitted, the
+    /// image is grabbed from the clipboard.
+    #[clap(long = "image")]
+    images: Vec<String>,
+
+    /// Run as a long-running Telegram bot instead of a one-shot CLI. Reads
+    /// the token from `TELEGRAM_BOT_TOKEN`.
+    #[clap(long)]
+    bot: bool,
+
+    /// Bypass the on-disk response cache and always hit the backend.
+    #[clap(long)]
+    no_cache: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+}
+
+/// Set while a completion request is in flight, so the Ctrl-C handler can
+/// tell a "stop this generation" press from a "quit the program" press.
+static GENERATING: AtomicBool = AtomicBool::new(false);
+/// Set by the Ctrl-C handler while [`GENERATING`]; polled by the streaming
+/// backend to cut the response short.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Pressing Ctrl-C during generation stops it and hands control back to the
+/// `USER:` prompt; pressing it while idle exits the process.
+fn install_ctrlc_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if GENERATING.load(Ordering::SeqCst) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        } else {
+            std::process::exit(0);
+        }
+    })?;
+    Ok(())
+}
+
+fn llama_from_args(args: &Args) -> LlamaCpp {
+    LlamaCpp {
+        host: args.host.to_string(),
+        port: args.port,
+        temperature: args.temperature,
+        n_predict: args.n_predict,
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    
-    let mut clip = arboard::Clipboard::new()?;
-    let img = clip.get_image()?;
-    let mut buf = Cursor::new(vec![]);
-
-    image::write_buffer_with_format(
-        &mut buf, 
-        &img.bytes, 
-        img.width as _, 
-        img.height as _, 
-        ColorType::Rgba8, 
-        ImageOutputFormat::Png
-    )?;
+    install_ctrlc_handler()?;
 
-    let b64 = BASE64_STANDARD.encode(buf.into_inner());
+    if let Some(Command::Search { query, top_k }) = &args.command {
+        return search::run(&llama_from_args(&args), query, *top_k);
+    }
 
-    let prompt = args.prompt_file
+    let prompt = args.prompt_file.clone()
         .map(std::fs::read_to_string)
         .transpose()?
-        .unwrap_or(args.prompt);
+        .unwrap_or_else(|| args.prompt.clone());
 
-    let mut req = Request {
-        prompt,
-        temperature: args.temperature,
-        n_predict: args.n_predict,
-        cache_prompt: true,
-        image_data: vec![ImData { data: b64, id: 1 }],
-        stop: vec!["USER:".to_string()]
+    // Embeddings for the search index always go through llama.cpp; images are
+    // only indexed when llama.cpp is also the backend producing the description.
+    let embed_llama = llama_from_args(&args);
+
+    // Identifies the backend+model that will answer, so the cache never
+    // serves one backend's answer in place of another's.
+    let backend_id = match args.backend {
+        BackendKind::Llama => format!("llama:{}:{}", args.host, args.port),
+        BackendKind::Openai => format!(
+            "openai:{}:{}",
+            args.openai_endpoint,
+            args.model.as_deref().unwrap_or("gpt-4-vision-preview"),
+        ),
+    };
+
+    let backend: Box<dyn Backend> = match args.backend {
+        BackendKind::Llama => Box::new(llama_from_args(&args)),
+        BackendKind::Openai => Box::new(OpenAiCompatible {
+            endpoint: args.openai_endpoint.clone(),
+            model: args.model.clone().unwrap_or_else(|| "gpt-4-vision-preview".to_string()),
+            n_predict: args.n_predict,
+            api_key: std::env::var("OPENAI_API_KEY")
+                .map_err(|_| anyhow::anyhow!("OPENAI_API_KEY must be set for --backend openai"))?,
+        }),
+    };
+
+    if args.bot {
+        let token = std::env::var("TELEGRAM_BOT_TOKEN")
+            .map_err(|_| anyhow::anyhow!("TELEGRAM_BOT_TOKEN must be set for --bot"))?;
+        return bot::run(backend.as_ref(), &token, &prompt);
+    }
+
+    let mut clip = arboard::Clipboard::new()?;
+
+    let images = if args.images.is_empty() {
+        let img = clip.get_image()?;
+        let mut buf = Cursor::new(vec![]);
+
+        image::write_buffer_with_format(
+            &mut buf,
+            &img.bytes,
+            img.width as _,
+            img.height as _,
+            ColorType::Rgba8,
+            ImageOutputFormat::Png
+        )?;
+
+        vec![ImageInput { data: BASE64_STANDARD.encode(buf.into_inner()), mime: "image/png".to_string() }]
+    } else {
+        args.images.iter()
+            .map(|spec| load_image(spec))
+            .collect::<Result<Vec<_>>>()?
     };
 
-    let endpoint = format!("http://{}:{}/completion", args.host, args.port);
-    let request = move |req: &Request| -> Result<String> {    
-        let resp: Response = ureq::post(&endpoint)
-            .send_json(req)?
-            .into_json()?;
+    let stream = atty::is(atty::Stream::Stdout);
 
-        Ok(resp.content)
+    // Returns the response together with whether Ctrl-C was pressed while it
+    // was being generated; the response itself is only cut short where the
+    // backend's streaming path stops early.
+    let request = |prompt: &str, images: &[ImageInput]| -> Result<(String, bool)> {
+        GENERATING.store(true, Ordering::SeqCst);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+
+        let resp = if stream {
+            backend.complete_stream(
+                prompt,
+                images,
+                &mut |token| {
+                    print!("{token}");
+                    let _ = std::io::stdout().flush();
+                },
+                &|| INTERRUPTED.load(Ordering::SeqCst),
+            )
+        } else {
+            backend.complete(prompt, images)
+        };
+
+        GENERATING.store(false, Ordering::SeqCst);
+        Ok((resp?, INTERRUPTED.swap(false, Ordering::SeqCst)))
     };
 
-    let resp = request(&req)?;
+    let cache_key = cache::key(&images, &prompt, args.temperature, args.n_predict, &backend_id)?;
+    let cached = if args.no_cache { None } else { cache::get(&cache_key)? };
+
+    let (resp, interrupted) = if let Some(cached) = cached {
+        println!("{prompt}{cached}");
+        (cached, false)
+    } else {
+        if stream {
+            print!("{prompt}");
+            std::io::stdout().flush()?;
+        }
+        let (resp, interrupted) = request(&prompt, &images)?;
+        if stream {
+            println!();
+        } else {
+            println!("{prompt}{resp}");
+        }
+
+        if !interrupted {
+            cache::put(&cache_key, &resp)?;
+
+            // Embedding goes through llama.cpp's /embedding endpoint, which
+            // may not be reachable when describing through another backend
+            // (e.g. a hosted --backend openai with no local server); skip
+            // indexing rather than fail the whole run over it.
+            if matches!(args.backend, BackendKind::Llama) {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                if let Err(e) = search::record(&embed_llama, &images, &resp, timestamp) {
+                    eprintln!("cliplm: failed to index image for search: {e}");
+                }
+            }
+        }
 
-    println!("{}{}", req.prompt, resp);
-    req.prompt.push_str(&resp);
+        (resp, interrupted)
+    };
+    let mut prompt = prompt;
+    // Discard whatever arrived before a Ctrl-C so the seed prompt for later
+    // turns never bakes in a truncated response.
+    if !interrupted {
+        prompt.push_str(&resp);
+    }
 
     if args.interactive {
         let mut line = String::new();
@@ -101,11 +283,26 @@ fn main() -> Result<()> {
             print!("USER: ");
             std::io::stdout().flush()?;
             std::io::stdin().read_line(&mut line)?;
-            
-            req.prompt.push_str(&format!("USER: {line}\nASSISTANT:"));
-            let resp = request(&req)?;
-            println!("ASSISTANT: {resp}");
-            req.prompt.push_str(&resp);
+
+            let turn_start = prompt.len();
+            prompt.push_str(&format!("USER: {line}\nASSISTANT:"));
+            if stream {
+                print!("ASSISTANT: ");
+                std::io::stdout().flush()?;
+            }
+            let (resp, interrupted) = request(&prompt, &images)?;
+            if stream {
+                println!();
+            } else {
+                println!("ASSISTANT: {resp}");
+            }
+
+            if interrupted {
+                // Back out the unanswered turn so the next prompt starts clean.
+                prompt.truncate(turn_start);
+            } else {
+                prompt.push_str(&resp);
+            }
         }
     } else if args.copy_back {
         clip.set_text(resp)?;
@@ -114,24 +311,23 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Serialize)]
-struct Request {
-    prompt: String,
-    temperature: f32,
-    n_predict: u32,
-    cache_prompt: bool,
-    image_data: Vec<ImData>,
-    stop: Vec<String>,
-}
+/// Resolves an `--image` argument to base64 image data, preserving its
+/// original encoding instead of re-encoding through the `image` crate.
+/// Accepts a `data:image/...;base64,...` URL or a path to a file on disk.
+fn load_image(spec: &str) -> Result<ImageInput> {
+    if let Some(data_url) = spec.strip_prefix("data:") {
+        let (mediatype, payload) = data_url.split_once(',')
+            .ok_or_else(|| anyhow::anyhow!("malformed data URL: {spec}"))?;
+        anyhow::ensure!(mediatype.starts_with("image/"), "not an image data URL: {mediatype}");
 
-#[derive(Serialize)]
-struct ImData {
-    data: String,
-    id: u32,
-}
+        BASE64_STANDARD.decode(payload)?; // validate before forwarding as-is
+        let mime = mediatype.split(';').next().unwrap_or(mediatype).to_string();
+        Ok(ImageInput { data: payload.to_string(), mime })
+    } else {
+        let mime = mime_guess::from_path(spec).first_or_octet_stream();
+        anyhow::ensure!(mime.type_() == mime_guess::mime::IMAGE, "not an image file: {spec} (guessed {mime})");
 
-#[derive(Deserialize)]
-struct Response {
-    content: String
+        let bytes = std::fs::read(spec)?;
+        Ok(ImageInput { data: BASE64_STANDARD.encode(bytes), mime: mime.to_string() })
+    }
 }
-
@@ -0,0 +1,113 @@
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use image::ImageOutputFormat;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::backend::{ImageInput, LlamaCpp};
+
+/// One described image in the local search history.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    timestamp: u64,
+    thumbnail_path: String,
+    description: String,
+    embedding: Vec<f32>,
+}
+
+/// Embeds `description` (and the image it describes) and appends it to the
+/// on-disk index, so it can later be found by [`run`].
+pub fn record(llama: &LlamaCpp, images: &[ImageInput], description: &str, timestamp: u64) -> Result<()> {
+    let embedding = normalize(llama.embed(description, images)?);
+    let thumbnail_path = save_thumbnail(images)?;
+
+    let entry = Entry { timestamp, thumbnail_path, description: description.to_string(), embedding };
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(index_path()?)?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+
+    Ok(())
+}
+
+/// Embeds `query` the same way and prints the `top_k` most similar entries
+/// in the index, ranked by cosine similarity.
+pub fn run(llama: &LlamaCpp, query: &str, top_k: usize) -> Result<()> {
+    let query_embedding = normalize(llama.embed(query, &[])?);
+
+    let mut scored: Vec<(f32, Entry)> = load_entries()?.into_iter()
+        .map(|e| {
+            let score = dot(&query_embedding, &e.embedding);
+            (score, e)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (score, entry) in scored.into_iter().take(top_k) {
+        println!("{score:.4}  {}  {}", entry.thumbnail_path, entry.description);
+    }
+
+    Ok(())
+}
+
+fn load_entries() -> Result<Vec<Entry>> {
+    let path = index_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Downscales the first image to a small thumbnail and saves it under a
+/// name derived from the original bytes' SHA-256, so re-recording the same
+/// image never collides with or clobbers another entry's thumbnail.
+fn save_thumbnail(images: &[ImageInput]) -> Result<String> {
+    let Some(image) = images.first() else {
+        return Ok(String::new());
+    };
+
+    let bytes = BASE64_STANDARD.decode(&image.data)?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+
+    let dir = data_dir()?.join("thumbnails");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{hash}.png"));
+
+    if !path.exists() {
+        let thumbnail = image::load_from_memory(&bytes)?.thumbnail(256, 256);
+        let mut buf = Cursor::new(Vec::new());
+        thumbnail.write_to(&mut buf, ImageOutputFormat::Png)?;
+        std::fs::write(&path, buf.into_inner())?;
+    }
+
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn index_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("search_index.jsonl"))
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("could not determine the user's data directory"))?
+        .join("cliplm");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn normalize(mut v: Vec<f32>) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+    v
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}